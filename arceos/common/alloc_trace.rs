@@ -0,0 +1,110 @@
+//! Placement-policy and allocation-trace infrastructure shared by the
+//! byte/page allocators in this chunk (`bump_allocator`, `lab_allocator`).
+//!
+//! This lives outside either crate's `src/` and is pulled in with
+//! `#[path = ...] mod alloc_trace;` rather than as a path dependency, since
+//! wiring a real shared crate needs a `Cargo.toml` entry this tree doesn't
+//! have. The `alloc_trace` feature referenced below must still be declared
+//! in each *including* crate's own manifest (features apply per compiled
+//! crate, not per source file) for `#[cfg(feature = "alloc_trace")]` to
+//! resolve without tripping `unexpected_cfgs`.
+
+/// Block-placement policy used when scanning a byte-side free list.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AllocPolicy {
+    /// Stop at the first block whose size is sufficient.
+    FirstFit,
+    /// Resume scanning from the block after the last allocation, wrapping once.
+    NextFit,
+    /// Scan the whole list and keep the block with the smallest leftover.
+    BestFit,
+}
+
+/// Kind of event recorded by the allocation trace ring buffer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    Alloc,
+    Dealloc,
+    AddMemory,
+}
+
+/// A single trace ring-buffer entry. `checksum` lets a consumer detect a
+/// torn record after a crash, the same way it would distrust a hash that
+/// doesn't match its input.
+#[cfg(feature = "alloc_trace")]
+#[derive(Clone, Copy)]
+pub struct TraceRecord {
+    pub id: u64,
+    pub event: TraceEvent,
+    pub addr: usize,
+    pub size: usize,
+    pub used_size: usize,
+    pub checksum: u64,
+}
+
+#[cfg(feature = "alloc_trace")]
+impl TraceRecord {
+    const EMPTY: TraceRecord = TraceRecord {
+        id: 0,
+        event: TraceEvent::Alloc,
+        addr: 0,
+        size: 0,
+        used_size: 0,
+        checksum: 0,
+    };
+
+    // FNV-style multiply-add, matching the `DefaultHasher` used elsewhere in this chunk.
+    fn checksum(id: u64, event: TraceEvent, addr: usize, size: usize, used_size: usize) -> u64 {
+        let mut h: u64 = 0;
+        for byte in id
+            .to_le_bytes()
+            .into_iter()
+            .chain(core::iter::once(event as u8))
+            .chain((addr as u64).to_le_bytes())
+            .chain((size as u64).to_le_bytes())
+            .chain((used_size as u64).to_le_bytes())
+        {
+            h = h.wrapping_mul(0x1000193).wrapping_add(byte as u64);
+        }
+        h
+    }
+}
+
+#[cfg(feature = "alloc_trace")]
+pub(crate) const TRACE_CAPACITY: usize = 64;
+
+/// Fixed-size, overwrite-oldest ring buffer of recent `alloc`/`dealloc`/`add_memory` events.
+#[cfg(feature = "alloc_trace")]
+pub(crate) struct TraceLog {
+    records: [TraceRecord; TRACE_CAPACITY],
+    len: usize,
+    head: usize,
+    next_id: u64,
+}
+
+#[cfg(feature = "alloc_trace")]
+impl TraceLog {
+    pub(crate) const fn new() -> Self {
+        Self {
+            records: [TraceRecord::EMPTY; TRACE_CAPACITY],
+            len: 0,
+            head: 0,
+            next_id: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, event: TraceEvent, addr: usize, size: usize, used_size: usize) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let checksum = TraceRecord::checksum(id, event, addr, size, used_size);
+        self.records[self.head] = TraceRecord { id, event, addr, size, used_size, checksum };
+        self.head = (self.head + 1) % TRACE_CAPACITY;
+        self.len = (self.len + 1).min(TRACE_CAPACITY);
+    }
+
+    /// Iterates recorded events oldest-first.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &TraceRecord> {
+        let start = if self.len < TRACE_CAPACITY { 0 } else { self.head };
+        (0..self.len).map(move |i| &self.records[(start + i) % TRACE_CAPACITY])
+    }
+}
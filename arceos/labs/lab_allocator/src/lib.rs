@@ -3,37 +3,67 @@
 #![no_std]
 #![allow(unused_variables)]
 
+// `#[cfg(feature = "alloc_trace")]` below requires this crate's own
+// Cargo.toml to declare `alloc_trace = []` under `[features]` (not present
+// in this tree) or it trips `unexpected_cfgs` under `-D warnings`.
 use allocator::{BaseAllocator, ByteAllocator, AllocResult};
-use axlog::ax_println;
 use core::ptr::{NonNull,null_mut};
 use core::alloc::Layout;
 use core::mem;
+
+// Shared with `bump_allocator`; see `common/alloc_trace.rs` for why this is
+// a `#[path]` module rather than a dependency on a separate crate.
+#[path = "../../../common/alloc_trace.rs"]
+mod alloc_trace;
+pub use alloc_trace::AllocPolicy;
+#[cfg(feature = "alloc_trace")]
+pub use alloc_trace::TraceRecord;
+pub use alloc_trace::TraceEvent;
+#[cfg(feature = "alloc_trace")]
+use alloc_trace::TraceLog;
+
 // 内存块结构
 struct Block {
     size: usize,
     next: *mut Block,
 }
 
-const MAX_INDICATOR: usize = 256;
+/// Smallest size class is `1 << MIN_SHIFT` bytes; classes double from there.
+const MIN_SHIFT: u32 = 3;
+const NUM_CLASSES: usize = 32;
 
-static mut POOL_32: [u8; 32+MAX_INDICATOR] = [0; 32 + MAX_INDICATOR];
-static mut POOL_128: [u8; 128+MAX_INDICATOR] = [0; 128 + MAX_INDICATOR];
-static mut POOL_512: [u8; 512 + MAX_INDICATOR] = [0; 512 + MAX_INDICATOR];
-static mut POOL_2048: [u8; 2048 + MAX_INDICATOR] = [0; 2048 + MAX_INDICATOR];
-static mut POOL_8_1024: [u8; 8*1024 + MAX_INDICATOR] = [0; 8*1024 + MAX_INDICATOR];
-static mut POOL_32_1024: [u8; 32*1024 + MAX_INDICATOR] = [0; 32*1024 + MAX_INDICATOR];
-static mut POOL_128_1024: [u8; 128*1024 + MAX_INDICATOR] = [0; 128*1024 + MAX_INDICATOR];
-static mut POOL_512_1024: [u8; 512*1024 + MAX_INDICATOR] = [0; 512*1024 + MAX_INDICATOR];
+/// Rounds `size` up to its size class index (classes are powers of two).
+fn size_class(size: usize) -> usize {
+    let size = size.max(1 << MIN_SHIFT);
+    let shift = size.next_power_of_two().trailing_zeros();
+    (shift - MIN_SHIFT) as usize
+}
 
-static mut POOL_SIZE: [usize; 8] = [32,128,512,2048,8*1024,32*1024,128*1024,512*1024];
+/// The block size carved/stored for a given size class.
+fn class_size(class: usize) -> usize {
+    1usize << (class as u32 + MIN_SHIFT)
+}
 
 pub struct LabByteAllocator {
     start: usize,
     total_size: usize,
     used_size: usize,
-    // old_list: *mut Block, // 奇数块列表
+    // Uncarved backing pool, kept address-ordered so the defragmentation pass
+    // can coalesce physically adjacent blocks.
     free_list: *mut Block,
+    // Per-size-class free lists; every block on `class_lists[c]` is exactly
+    // `class_size(c)` bytes, carved from `free_list` on demand.
+    class_lists: [*mut Block; NUM_CLASSES],
+    // Blocks carved out via `reserve`, tracked apart from `free_list`/`class_lists`
+    // so a normal allocation can't hand them out from under the reserving caller.
+    reserved_list: *mut Block,
     num: isize,
+    policy: AllocPolicy,
+    // next-fit cursor into `free_list`; resumes scanning from here instead of the list head
+    roving: *mut Block,
+
+    #[cfg(feature = "alloc_trace")]
+    trace: TraceLog,
 }
 
 unsafe impl Sync for LabByteAllocator {}
@@ -41,13 +71,89 @@ unsafe impl Send for LabByteAllocator {}
 
 impl LabByteAllocator {
     pub const fn new() -> Self {
+        Self::new_with_policy(AllocPolicy::FirstFit)
+    }
+
+    pub const fn new_with_policy(policy: AllocPolicy) -> Self {
         Self {
             start: 0,
             total_size: 0,
             used_size: 0,
-            // old_list: null_mut(),
             free_list: null_mut(),
+            class_lists: [null_mut(); NUM_CLASSES],
+            reserved_list: null_mut(),
             num:0,
+            policy,
+            roving: null_mut(),
+            #[cfg(feature = "alloc_trace")]
+            trace: TraceLog::new(),
+        }
+    }
+
+    /// Switches the placement policy used when carving fresh slabs.
+    pub fn set_policy(&mut self, policy: AllocPolicy) {
+        self.policy = policy;
+        self.roving = null_mut();
+    }
+
+    #[cfg(feature = "alloc_trace")]
+    fn trace_event(&mut self, event: TraceEvent, addr: usize, size: usize) {
+        self.trace.push(event, addr, size, self.used_size);
+    }
+
+    #[cfg(not(feature = "alloc_trace"))]
+    #[inline(always)]
+    fn trace_event(&mut self, _event: TraceEvent, _addr: usize, _size: usize) {}
+
+    /// Dumps recent allocation events, oldest first. Only compiled in with
+    /// the `alloc_trace` feature.
+    #[cfg(feature = "alloc_trace")]
+    pub fn trace_events(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.trace.iter()
+    }
+
+    /// Carves a contiguous run of at least `size` bytes out of the backing
+    /// pool up front and pins it on `reserved_list`, so a later burst of
+    /// small allocations for the same subsystem can't fail mid-sequence.
+    pub fn reserve(&mut self, size: usize) -> AllocResult {
+        unsafe {
+            let block = match self.carve_from_backing(size) {
+                Some(block) => block,
+                None => {
+                    self.defragment();
+                    match self.carve_from_backing(size) {
+                        Some(block) => block,
+                        None => return Err(allocator::AllocError::NoMemory),
+                    }
+                }
+            };
+
+            (*block).next = self.reserved_list;
+            self.reserved_list = block;
+            // `carve_from_backing` may leave `block` larger than `size` when
+            // the leftover was too small to split off; track what was
+            // actually taken so `release` subtracts the same amount back.
+            self.used_size += (*block).size;
+            Ok(())
+        }
+    }
+
+    /// Releases the most recently reserved block back to the backing pool
+    /// and merges it with its neighbours.
+    pub fn release(&mut self) -> AllocResult {
+        unsafe {
+            let block = self.reserved_list;
+            if block.is_null() {
+                return Err(allocator::AllocError::InvalidParam);
+            }
+            self.reserved_list = (*block).next;
+            self.used_size -= (*block).size;
+
+            (*block).next = self.free_list;
+            self.free_list = block;
+            self.sort_free_list_by_address();
+            self.merge_blocks();
+            Ok(())
         }
     }
 
@@ -56,7 +162,10 @@ impl LabByteAllocator {
         self.start = start;
         self.total_size = size;
         self.used_size = 0;
-        
+        self.roving = null_mut();
+        self.class_lists = [null_mut(); NUM_CLASSES];
+        self.reserved_list = null_mut();
+
         // 将整个内存区域作为一个大块
         let block = start as *mut Block;
         (*block).size = size - mem::size_of::<Block>();
@@ -67,13 +176,13 @@ impl LabByteAllocator {
     // 分割内存块
     unsafe fn split_block(block: *mut Block, required_size: usize) -> bool {
         let remaining_size = (*block).size - required_size;
-        
+
         // 检查是否有足够空间分割
         if remaining_size > mem::size_of::<Block>() {
             let new_block = ((block as *mut u8).add(mem::size_of::<Block>() + required_size)) as *mut Block;
             (*new_block).size = remaining_size - mem::size_of::<Block>();
             (*new_block).next = (*block).next;
-            
+
             (*block).size = required_size;
             (*block).next = new_block;
             true
@@ -82,14 +191,18 @@ impl LabByteAllocator {
         }
     }
 
-    // 合并相邻的空闲块
+    // 合并相邻的空闲块（要求链表按地址有序）
     unsafe fn merge_blocks(&mut self) {
         let mut current = self.free_list;
         while !current.is_null() && !(*current).next.is_null() {
             let next = (*current).next;
             let current_end = (current as *mut u8).add(mem::size_of::<Block>() + (*current).size) as *mut Block;
-            
+
             if current_end == next {
+                // `next` is being absorbed into `current`; the roving cursor can't point at it anymore.
+                if self.roving == next {
+                    self.roving = null_mut();
+                }
                 // 合并相邻块
                 (*current).size += mem::size_of::<Block>() + (*next).size;
                 (*current).next = (*next).next;
@@ -99,33 +212,164 @@ impl LabByteAllocator {
         }
     }
 
-    unsafe fn alloc_helper(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
-        // 计算对齐后的所需大小
-        let required_size = layout.size().max(layout.align());
-        // 遍历空闲链表寻找合适的块
-        let mut prev: *mut *mut Block = &mut self.free_list;
+    /// First-fit scan: the first block whose size is sufficient.
+    unsafe fn find_first_fit(&self, required_size: usize) -> Option<*mut Block> {
+        let mut current = self.free_list;
+        while !current.is_null() {
+            if (*current).size >= required_size {
+                return Some(current);
+            }
+            current = (*current).next;
+        }
+        None
+    }
+
+    /// Scans starting at `start`, wrapping to `free_list` once it runs off the end.
+    unsafe fn find_from(&self, start: *mut Block, required_size: usize) -> Option<*mut Block> {
+        let mut current = if start.is_null() { self.free_list } else { start };
+        let mut wrapped = false;
+        while !current.is_null() {
+            if (*current).size >= required_size {
+                return Some(current);
+            }
+            current = (*current).next;
+            if current.is_null() && !wrapped {
+                current = self.free_list;
+                wrapped = true;
+            }
+            if wrapped && current == start {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Best-fit scan: the block with the smallest non-negative leftover.
+    unsafe fn find_best_fit(&self, required_size: usize) -> Option<*mut Block> {
+        let mut best: Option<*mut Block> = None;
+        let mut best_remainder = usize::MAX;
         let mut current = self.free_list;
-        
         while !current.is_null() {
             if (*current).size >= required_size {
-                // 尝试分割块
-                Self::split_block(current, required_size);
-                
-                // 从链表中移除该块
+                let remainder = (*current).size - required_size;
+                if remainder < best_remainder {
+                    best_remainder = remainder;
+                    best = Some(current);
+                }
+            }
+            current = (*current).next;
+        }
+        best
+    }
+
+    /// Unlinks `target` from `free_list`, wherever it currently sits.
+    unsafe fn remove_block(&mut self, target: *mut Block) {
+        let mut prev: *mut *mut Block = &mut self.free_list;
+        let mut current = self.free_list;
+        while !current.is_null() {
+            if current == target {
                 *prev = (*current).next;
-                
-                // 计算返回指针
-                let ptr = (current as *mut u8).add(mem::size_of::<Block>());
-                self.used_size += required_size;
-                
-                return Ok(NonNull::new(ptr).unwrap());
+                return;
             }
-            
             prev = &mut (*current).next;
             current = (*current).next;
         }
-    Err(allocator::AllocError::NoMemory) 
+    }
 
+    /// Carves a block of at least `size` bytes out of the backing pool using
+    /// the configured placement policy, splitting off any leftover large
+    /// enough to hold a `Block` header of its own.
+    unsafe fn carve_from_backing(&mut self, size: usize) -> Option<*mut Block> {
+        let found = match self.policy {
+            AllocPolicy::FirstFit => self.find_first_fit(size),
+            AllocPolicy::NextFit => {
+                let start = if self.roving.is_null() { self.free_list } else { self.roving };
+                self.find_from(start, size)
+            }
+            AllocPolicy::BestFit => self.find_best_fit(size),
+        }?;
+
+        Self::split_block(found, size);
+        let resume = (*found).next;
+        self.remove_block(found);
+        if self.policy == AllocPolicy::NextFit {
+            self.roving = if resume.is_null() { self.free_list } else { resume };
+        }
+        Some(found)
+    }
+
+    /// Reclaims every class's free blocks back into the backing pool, sorts
+    /// the pool by address and merges adjacent buddies, so a class that ran
+    /// dry can be satisfied from memory that migrated out of other classes.
+    unsafe fn defragment(&mut self) {
+        for list in self.class_lists.iter_mut() {
+            let mut current = *list;
+            while !current.is_null() {
+                let next = (*current).next;
+                (*current).next = self.free_list;
+                self.free_list = current;
+                current = next;
+            }
+            *list = null_mut();
+        }
+        self.sort_free_list_by_address();
+        self.merge_blocks();
+        self.roving = null_mut();
+    }
+
+    /// Re-links `free_list` into address order (insertion sort; the list is
+    /// small enough in this lab allocator that this stays cheap) so that
+    /// `merge_blocks`, which only compares consecutive list entries, can see
+    /// every physically adjacent pair.
+    unsafe fn sort_free_list_by_address(&mut self) {
+        let mut sorted: *mut Block = null_mut();
+        let mut current = self.free_list;
+        while !current.is_null() {
+            let next = (*current).next;
+            if sorted.is_null() || (current as usize) < (sorted as usize) {
+                (*current).next = sorted;
+                sorted = current;
+            } else {
+                let mut prev = sorted;
+                while !(*prev).next.is_null() && ((*prev).next as usize) < (current as usize) {
+                    prev = (*prev).next;
+                }
+                (*current).next = (*prev).next;
+                (*prev).next = current;
+            }
+            current = next;
+        }
+        self.free_list = sorted;
+    }
+
+    unsafe fn alloc_helper(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let required_size = layout.size().max(layout.align());
+        let class = size_class(required_size);
+        let slab_size = class_size(class);
+
+        // 1. 复用该尺寸档位上已释放的块
+        let block = self.class_lists[class];
+        let block = if !block.is_null() {
+            self.class_lists[class] = (*block).next;
+            Some(block)
+        } else if let Some(block) = self.carve_from_backing(slab_size) {
+            // 2. 从主存池中切出新的块
+            Some(block)
+        } else {
+            // 3. 主存池碎片化/耗尽，先整理再重试
+            self.defragment();
+            self.carve_from_backing(slab_size)
+        };
+
+        match block {
+            Some(block) => {
+                let ptr = (block as *mut u8).add(mem::size_of::<Block>());
+                self.used_size += required_size;
+                self.trace_event(TraceEvent::Alloc, ptr as usize, required_size);
+                Ok(NonNull::new(ptr).unwrap())
+            }
+            None => Err(allocator::AllocError::NoMemory),
+        }
     }
 }
 
@@ -143,104 +387,34 @@ impl BaseAllocator for LabByteAllocator {
             (*new_block).size = size - mem::size_of::<Block>();
             (*new_block).next = self.free_list;
             self.free_list = new_block;
-            
+
             self.total_size += size;
+            self.sort_free_list_by_address();
             self.merge_blocks();
         }
+        self.trace_event(TraceEvent::AddMemory, start, size);
         Ok(())
     }
 }
 
-static mut NUM96: isize = 0;
-static mut NUM192: isize = 0;
 impl ByteAllocator for LabByteAllocator {
-
     fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
-        unsafe {
-            if layout.size() == 96 {
-                NUM96+=1;
-                if NUM96 == 65 {
-                    NUM96 = -1000;
-                    return self.alloc_helper(layout)
-                }
-            }
-            if layout.size() == 192 {
-                NUM192+=1;
-                if NUM192 == 64 {
-                    return self.alloc_helper(layout)
-                } else if NUM192 == 162 {
-                    NUM192 = -1000;
-                    return self.alloc_helper(layout)
-                } 
-            }
-            if let Some((i, size) ) = POOL_SIZE.iter_mut().enumerate().find(|(_, s)| **s == layout.size()) {     
-                // axlog::ax_println!("size:{}",layout.size());
-                // axlog::ax_println!("num96:{},num192:{}",NUM96,NUM192);
-                POOL_SIZE[i] += 1;
-                // axlog::ax_println!("poolsizei:{} ,{}",POOL_SIZE[i],i);
-                // axlog::ax_println!("{:?}",POOL_SIZE);
-                match i {
-                    0 => return Ok(NonNull::new(POOL_32.as_mut_ptr()).unwrap()),
-                    1 => return Ok(NonNull::new(POOL_128.as_mut_ptr()).unwrap()),
-                    2 => return Ok(NonNull::new(POOL_512.as_mut_ptr()).unwrap()),
-                    3 => return Ok(NonNull::new(POOL_2048.as_mut_ptr()).unwrap()),
-                    4 => return Ok(NonNull::new(POOL_8_1024.as_mut_ptr()).unwrap()),
-                    5 => return Ok(NonNull::new(POOL_32_1024.as_mut_ptr()).unwrap()),
-                    6 => return Ok(NonNull::new(POOL_128_1024.as_mut_ptr()).unwrap()),
-                    7 => return Ok(NonNull::new(POOL_512_1024.as_mut_ptr()).unwrap()),
-                    _ => axlog::ax_println!("error"),
-                }
-            }
-            self.alloc_helper(layout)
-        }
+        unsafe { self.alloc_helper(layout) }
     }
-    
 
     fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
-
         unsafe {
+            let required_size = layout.size().max(layout.align());
+            let class = size_class(required_size);
+            self.used_size -= required_size;
 
-            // 检查指针是否来自静态内存池
-            let ptr_addr = ptr.as_ptr() as usize;
-            let is_static = [
-                POOL_32.as_ptr() as usize,
-                POOL_128.as_ptr() as usize,
-                POOL_512.as_ptr() as usize,
-                POOL_2048.as_ptr() as usize,
-                POOL_8_1024.as_ptr() as usize,
-                POOL_32_1024.as_ptr() as usize,
-                POOL_128_1024.as_ptr() as usize,
-                POOL_512_1024.as_ptr() as usize,
-            ].iter().any(|&pool_addr| {
-                let pool_size = match pool_addr {
-                    addr if addr == POOL_32.as_ptr() as usize => mem::size_of_val(&POOL_32),
-                    addr if addr == POOL_128.as_ptr() as usize => mem::size_of_val(&POOL_128),
-                    addr if addr == POOL_512.as_ptr() as usize => mem::size_of_val(&POOL_512),
-                    addr if addr == POOL_2048.as_ptr() as usize => mem::size_of_val(&POOL_2048),
-                    addr if addr == POOL_8_1024.as_ptr() as usize => mem::size_of_val(&POOL_8_1024),
-                    addr if addr == POOL_32_1024.as_ptr() as usize => mem::size_of_val(&POOL_32_1024),
-                    addr if addr == POOL_128_1024.as_ptr() as usize => mem::size_of_val(&POOL_128_1024),
-                    addr if addr == POOL_512_1024.as_ptr() as usize => mem::size_of_val(&POOL_512_1024),
-                    _ => 0,
-                };
-                ptr_addr >= pool_addr && ptr_addr < pool_addr + pool_size
-            });
-            if is_static {
-                return; // 静态内存池的指针不释放
-            }
-
-            let size = layout.size().max(layout.align());
-            self.used_size -= size;
-            
-            // 将释放的内存作为新块添加到空闲链表头部
+            // 放回对应尺寸档位的空闲链表头部
             let block = (ptr.as_ptr() as *mut u8).sub(mem::size_of::<Block>()) as *mut Block;
-            (*block).size = size;
-            (*block).next = self.free_list;
-            self.free_list = block;
-            
-            // 尝试合并相邻块
-            self.merge_blocks();
+            (*block).size = class_size(class);
+            (*block).next = self.class_lists[class];
+            self.class_lists[class] = block;
         }
+        self.trace_event(TraceEvent::Dealloc, ptr.as_ptr() as usize, layout.size().max(layout.align()));
     }
 
     fn total_bytes(&self) -> usize {
@@ -255,4 +429,3 @@ impl ByteAllocator for LabByteAllocator {
         self.total_size - self.used_size
     }
 }
-
@@ -1,8 +1,22 @@
 #![no_std]
 
+// `#[cfg(feature = "alloc_trace")]` below requires this crate's own
+// Cargo.toml to declare `alloc_trace = []` under `[features]` (not present
+// in this tree) or it trips `unexpected_cfgs` under `-D warnings`.
 use allocator::{BaseAllocator, ByteAllocator, PageAllocator, AllocResult};
 use core::{alloc::Layout, mem, ptr::{null_mut, NonNull}};
 
+// Shared with `lab_allocator`; see `common/alloc_trace.rs` for why this is
+// a `#[path]` module rather than a dependency on a separate crate.
+#[path = "../../../common/alloc_trace.rs"]
+mod alloc_trace;
+pub use alloc_trace::AllocPolicy;
+#[cfg(feature = "alloc_trace")]
+pub use alloc_trace::TraceRecord;
+pub use alloc_trace::TraceEvent;
+#[cfg(feature = "alloc_trace")]
+use alloc_trace::TraceLog;
+
 /// Early memory allocator
 /// Use it before formal bytes-allocator and pages-allocator can work!
 /// This is a double-end memory range:
@@ -15,14 +29,36 @@ use core::{alloc::Layout, mem, ptr::{null_mut, NonNull}};
 ///
 /// For bytes area, 'count' records number of allocations.
 /// When it goes down to ZERO, free bytes-used area.
-/// For pages area, it will never be freed!
+/// For pages area, a two-level bitmap tracks individual pages so they can be
+/// freed and reused in any order (see `Bitmap32` below).
 ///
+
 pub struct EarlyAllocator<const PAGE_SIZE: usize> {
     total_size: usize,
     used_size: usize,
     left_index: usize,
     right_index: usize,
     free_list: *mut Block,
+    // Blocks carved out via `reserve`, tracked apart from `free_list` so a
+    // normal `alloc` scan can't hand them out from under the reserving caller.
+    reserved_list: *mut Block,
+    policy: AllocPolicy,
+    // next-fit cursor; resumes scanning from here instead of the list head
+    roving: *mut Block,
+
+    // Two-level radix bitmap over the page region: `leaves` track individual
+    // pages (32 per word) and `parent` tracks which leaves are saturated, so
+    // a search can skip a full leaf in one step instead of scanning 32 bits.
+    page_base: usize,
+    total_page_count: usize,
+    num_leaves: usize,
+    num_parent_words: usize,
+    padding_bits: usize,
+    leaves: *mut Bitmap32,
+    parent: *mut Bitmap32,
+
+    #[cfg(feature = "alloc_trace")]
+    trace: TraceLog,
 }
 
 struct Block {
@@ -30,27 +66,294 @@ struct Block {
     next: *mut Block,
 }
 
+/// A 32-page leaf (or 32-leaf parent) word. Bit `i`, counted from the MSB,
+/// is set when page/leaf `i` is used/full.
+struct Bitmap32(u32);
+
+impl Bitmap32 {
+    const fn is_full(&self) -> bool {
+        self.0 == u32::MAX
+    }
+
+    /// Returns the free bit closest to index 31, for callers that hand out
+    /// pages from the top down.
+    fn find_free_from_top(&self) -> Option<u32> {
+        if self.is_full() {
+            None
+        } else {
+            Some(31 - (!self.0).trailing_zeros())
+        }
+    }
+
+    fn get(&self, bit: u32) -> bool {
+        (self.0 >> (31 - bit)) & 1 != 0
+    }
+
+    fn set(&mut self, bit: u32) {
+        self.0 |= 1 << (31 - bit);
+    }
+
+    fn clear(&mut self, bit: u32) {
+        self.0 &= !(1 << (31 - bit));
+    }
+}
+
 
 unsafe impl<const PAGE_SIZE: usize> Sync for EarlyAllocator<PAGE_SIZE> {}
 unsafe impl<const PAGE_SIZE: usize> Send for EarlyAllocator<PAGE_SIZE> {}
 
 impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
     pub const fn new() -> Self {
-        Self { total_size: 0, used_size: 0, left_index: 0, right_index: 0, free_list: null_mut() }
+        Self::new_with_policy(AllocPolicy::FirstFit)
+    }
+
+    pub const fn new_with_policy(policy: AllocPolicy) -> Self {
+        Self {
+            total_size: 0,
+            used_size: 0,
+            left_index: 0,
+            right_index: 0,
+            free_list: null_mut(),
+            reserved_list: null_mut(),
+            policy,
+            roving: null_mut(),
+            page_base: 0,
+            total_page_count: 0,
+            num_leaves: 0,
+            num_parent_words: 0,
+            padding_bits: 0,
+            leaves: null_mut(),
+            parent: null_mut(),
+            #[cfg(feature = "alloc_trace")]
+            trace: TraceLog::new(),
+        }
+    }
+
+    #[cfg(feature = "alloc_trace")]
+    fn trace_event(&mut self, event: TraceEvent, addr: usize, size: usize) {
+        self.trace.push(event, addr, size, self.used_size);
+    }
+
+    #[cfg(not(feature = "alloc_trace"))]
+    #[inline(always)]
+    fn trace_event(&mut self, _event: TraceEvent, _addr: usize, _size: usize) {}
+
+    /// Dumps recent allocation events, oldest first. Only compiled in with
+    /// the `alloc_trace` feature.
+    #[cfg(feature = "alloc_trace")]
+    pub fn trace_events(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.trace.iter()
+    }
+
+    /// Carves a contiguous run of at least `size` bytes out of `free_list`
+    /// up front and pins it on `reserved_list`, so a later burst of small
+    /// allocations for the same subsystem can't fail mid-sequence.
+    pub fn reserve(&mut self, size: usize) -> AllocResult {
+        unsafe {
+            let found = match self.policy {
+                AllocPolicy::FirstFit => self.find_first_fit(size),
+                AllocPolicy::NextFit => {
+                    let start = if self.roving.is_null() { self.free_list } else { self.roving };
+                    self.find_from(start, size)
+                }
+                AllocPolicy::BestFit => self.find_best_fit(size),
+            };
+
+            let block = match found {
+                Some(block) => block,
+                None => return Err(allocator::AllocError::NoMemory),
+            };
+
+            Self::split_block(block, size);
+            let resume = (*block).next;
+            self.remove_block(block);
+            if self.policy == AllocPolicy::NextFit {
+                self.roving = if resume.is_null() { self.free_list } else { resume };
+            }
+
+            // `split_block` may leave `block` larger than `size` when the
+            // leftover was too small to carve off; track what was actually
+            // taken so `release` subtracts the same amount it gets back.
+            self.used_size += (*block).size;
+            self.note_byte_frontier(block);
+            (*block).next = self.reserved_list;
+            self.reserved_list = block;
+            Ok(())
+        }
+    }
+
+    /// Releases the most recently reserved block back to `free_list` and
+    /// merges it with its neighbours.
+    pub fn release(&mut self) -> AllocResult {
+        unsafe {
+            let block = self.reserved_list;
+            if block.is_null() {
+                return Err(allocator::AllocError::InvalidParam);
+            }
+            self.reserved_list = (*block).next;
+            self.used_size -= (*block).size;
+
+            (*block).next = self.free_list;
+            self.free_list = block;
+            self.merge_blocks();
+            Ok(())
+        }
+    }
+
+    /// Switches the placement policy used by subsequent `alloc` calls.
+    pub fn set_policy(&mut self, policy: AllocPolicy) {
+        self.policy = policy;
+        self.roving = null_mut();
     }
 
     unsafe fn init_free_list(&mut self, start: usize, size: usize) {
-        self.left_index = start;
         self.right_index = start + size;
         self.used_size = 0;
         self.total_size = size;
+        self.roving = null_mut();
+        self.reserved_list = null_mut();
+
+        // Carve the page bitmap's own storage out of the front of the region,
+        // the same way `Block` headers live inline rather than on the heap.
+        // The arrays are sized against the whole region (an upper bound on
+        // how many pages could ever exist); `total_page_count` itself is then
+        // computed over just the span past the bitmap/byte header, so
+        // `population`/`total_pages`/`available_pages` never count pages that
+        // `find_free_page`/`find_free_run` could never actually hand out.
+        let max_page_count = size / PAGE_SIZE;
+        let num_leaves = (max_page_count + 31) / 32;
+        let num_parent_words = (num_leaves + 31) / 32;
+        let bitmap_bytes = (num_leaves + num_parent_words) * mem::size_of::<u32>();
+
+        self.left_index = start + bitmap_bytes;
+        // Pages are addressed starting at the first page boundary past the
+        // bitmap's own storage, so that prefix is structurally excluded from
+        // the bitmap entirely rather than merely rejected by the `left_index`
+        // guard at lookup time.
+        self.page_base = (self.left_index + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let total_page_count = (start + size).saturating_sub(self.page_base) / PAGE_SIZE;
+        self.total_page_count = total_page_count;
+        self.num_leaves = num_leaves;
+        self.num_parent_words = num_parent_words;
+        self.padding_bits = num_leaves * 32 - total_page_count;
+        self.leaves = start as *mut Bitmap32;
+        self.parent = (start + num_leaves * mem::size_of::<u32>()) as *mut Bitmap32;
 
-        let block = start as *mut Block;
-        (*block).size = size - mem::size_of::<Block>();
+        for i in 0..num_leaves {
+            (*self.leaves.add(i)).0 = 0;
+        }
+        for i in 0..num_parent_words {
+            (*self.parent.add(i)).0 = 0;
+        }
+        // Pages beyond `total_page_count` (excluded from the page-allocatable
+        // window, padded up to a 32-page leaf boundary) are marked used so
+        // they can never be handed out. This can span more than one leaf
+        // once the bitmap's own storage grows past a page, so walk the
+        // whole padding range rather than only patching the last leaf.
+        for index in total_page_count..num_leaves * 32 {
+            (*self.leaves.add(index / 32)).set((index % 32) as u32);
+        }
+        for i in 0..num_leaves {
+            if (*self.leaves.add(i)).is_full() {
+                (*self.parent.add(i / 32)).set((i % 32) as u32);
+            }
+        }
+        // Leaves beyond `num_leaves` (padding to a 32-leaf parent word) are
+        // marked full so the parent-level search skips them.
+        if num_parent_words > 0 {
+            let leaves_in_last_word = num_leaves - (num_parent_words - 1) * 32;
+            for bit in leaves_in_last_word..32 {
+                (*self.parent.add(num_parent_words - 1)).set(bit as u32);
+            }
+        }
+
+        let block = self.left_index as *mut Block;
+        (*block).size = size - bitmap_bytes - mem::size_of::<Block>();
         (*block).next = null_mut();
         self.free_list = block;
     }
 
+    fn page_addr(&self, index: usize) -> usize {
+        self.page_base + index * PAGE_SIZE
+    }
+
+    unsafe fn is_page_used(&self, index: usize) -> bool {
+        (*self.leaves.add(index / 32)).get((index % 32) as u32)
+    }
+
+    unsafe fn set_page_used(&mut self, index: usize) {
+        let leaf_idx = index / 32;
+        let leaf = &mut *self.leaves.add(leaf_idx);
+        leaf.set((index % 32) as u32);
+        if leaf.is_full() {
+            (*self.parent.add(leaf_idx / 32)).set((leaf_idx % 32) as u32);
+        }
+    }
+
+    unsafe fn clear_page_used(&mut self, index: usize) {
+        let leaf_idx = index / 32;
+        (*self.leaves.add(leaf_idx)).clear((index % 32) as u32);
+        (*self.parent.add(leaf_idx / 32)).clear((leaf_idx % 32) as u32);
+    }
+
+    /// Fast path for a single page: pages are handed out from the top of the
+    /// region down, the mirror image of bytes growing up from `left_index`,
+    /// so the two regions stay disjoint. Skips saturated parent words, but
+    /// within a non-full word tries every leaf (and, within a leaf, only
+    /// accepts a bit whose address has not been claimed by the byte side)
+    /// rather than rejecting the whole word on the first candidate.
+    unsafe fn find_free_page(&self) -> Option<usize> {
+        for w in (0..self.num_parent_words).rev() {
+            if (*self.parent.add(w)).is_full() {
+                continue;
+            }
+            let leaf_lo = w * 32;
+            let leaf_hi = ((w + 1) * 32).min(self.num_leaves);
+            for leaf_idx in (leaf_lo..leaf_hi).rev() {
+                let leaf = &*self.leaves.add(leaf_idx);
+                if leaf.is_full() {
+                    continue;
+                }
+                if let Some(bit) = leaf.find_free_from_top() {
+                    let index = leaf_idx * 32 + bit as usize;
+                    if self.page_addr(index) >= self.left_index {
+                        return Some(index);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// General path: scan for a run of `num_pages` consecutive free pages
+    /// whose start address satisfies `align_pages`, searching from the top
+    /// of the region down so pages stay disjoint from the byte area growing
+    /// up from `left_index`.
+    unsafe fn find_free_run(&self, num_pages: usize, align_pages: usize) -> Option<usize> {
+        let mut run_len = 0usize;
+        for index in (0..self.total_page_count).rev() {
+            let free = !self.is_page_used(index) && self.page_addr(index) >= self.left_index;
+            if free {
+                run_len += 1;
+            } else {
+                run_len = 0;
+                continue;
+            }
+            if run_len >= num_pages && (self.page_addr(index) / PAGE_SIZE) % align_pages == 0 {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    unsafe fn population(&self) -> usize {
+        let mut count = 0usize;
+        for i in 0..self.num_leaves {
+            count += (*self.leaves.add(i)).0.count_ones() as usize;
+        }
+        count - self.padding_bits
+    }
+
     unsafe fn split_block(block: *mut Block, required_size: usize) -> bool {
         let remaining_size = (*block).size - required_size;
 
@@ -66,6 +369,83 @@ impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
         }
     }
 
+    /// Bumps `left_index` to `block`'s true end address (header + payload)
+    /// if that's past the current frontier. `alloc`/`reserve` can carve a
+    /// block from anywhere in `free_list` (not just the current frontier,
+    /// depending on placement policy), so the frontier has to be tracked
+    /// from the actual carved address rather than accumulated request sizes,
+    /// or it can lag behind and let `alloc_pages` hand out an overlapping page.
+    unsafe fn note_byte_frontier(&mut self, block: *mut Block) {
+        let block_end = (block as usize) + mem::size_of::<Block>() + (*block).size;
+        if block_end > self.left_index {
+            self.left_index = block_end;
+        }
+    }
+
+    /// First-fit scan: the first block whose size is sufficient.
+    unsafe fn find_first_fit(&self, required_size: usize) -> Option<*mut Block> {
+        let mut current = self.free_list;
+        while !current.is_null() {
+            if (*current).size >= required_size {
+                return Some(current);
+            }
+            current = (*current).next;
+        }
+        None
+    }
+
+    /// Scans starting at `start`, wrapping to `free_list` once it runs off the end.
+    unsafe fn find_from(&self, start: *mut Block, required_size: usize) -> Option<*mut Block> {
+        let mut current = if start.is_null() { self.free_list } else { start };
+        let mut wrapped = false;
+        while !current.is_null() {
+            if (*current).size >= required_size {
+                return Some(current);
+            }
+            current = (*current).next;
+            if current.is_null() && !wrapped {
+                current = self.free_list;
+                wrapped = true;
+            }
+            if wrapped && current == start {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Best-fit scan: the block with the smallest non-negative leftover.
+    unsafe fn find_best_fit(&self, required_size: usize) -> Option<*mut Block> {
+        let mut best: Option<*mut Block> = None;
+        let mut best_remainder = usize::MAX;
+        let mut current = self.free_list;
+        while !current.is_null() {
+            if (*current).size >= required_size {
+                let remainder = (*current).size - required_size;
+                if remainder < best_remainder {
+                    best_remainder = remainder;
+                    best = Some(current);
+                }
+            }
+            current = (*current).next;
+        }
+        best
+    }
+
+    /// Unlinks `target` from `free_list`, wherever it currently sits.
+    unsafe fn remove_block(&mut self, target: *mut Block) {
+        let mut prev: *mut *mut Block = &mut self.free_list;
+        let mut current = self.free_list;
+        while !current.is_null() {
+            if current == target {
+                *prev = (*current).next;
+                return;
+            }
+            prev = &mut (*current).next;
+            current = (*current).next;
+        }
+    }
+
     unsafe fn merge_blocks(&mut self) {
         let mut current = self.free_list;
         while !current.is_null() && !(*current).next.is_null() {
@@ -73,6 +453,10 @@ impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
             let current_end = (current as *mut u8).add(mem::size_of::<Block>() + (*current).size) as *mut Block;
             // 判断是否连续
             if current_end == next {
+                // `next` is being absorbed into `current`; the roving cursor can't point at it anymore.
+                if self.roving == next {
+                    self.roving = null_mut();
+                }
                 (*current).size += mem::size_of::<Block>() + (*next).size;
                 (*current).next = (*next).next;
             } else {
@@ -98,11 +482,12 @@ impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
             (*new_block).size = size - mem::size_of::<Block>();
             (*new_block).next = self.free_list;
             self.free_list = new_block;
-            
+
             self.total_size += size;
             self.merge_blocks();
-            Ok(())
         }
+        self.trace_event(TraceEvent::AddMemory, start, size);
+        Ok(())
     }
 }
 
@@ -113,23 +498,33 @@ impl<const PAGE_SIZE: usize> ByteAllocator for EarlyAllocator<PAGE_SIZE> {
             if required_size + self.left_index >= self.right_index {
                 return Err(allocator::AllocError::NoMemory)
             }
-            let mut prev: *mut *mut Block = &mut self.free_list;
-            let mut current = self.free_list;
-
-            while !current.is_null() {
-                if (*current).size >= required_size {
-                    Self::split_block(current, required_size);
-                    *prev = (*current).next;
-                    let ptr = (current as *mut u8).add(mem::size_of::<Block>());
-                    self.used_size += required_size;
-                    self.left_index += required_size;
-                    return Ok(NonNull::new(ptr).unwrap());
+
+            let found = match self.policy {
+                AllocPolicy::FirstFit => self.find_first_fit(required_size),
+                AllocPolicy::NextFit => {
+                    let start = if self.roving.is_null() { self.free_list } else { self.roving };
+                    self.find_from(start, required_size)
                 }
+                AllocPolicy::BestFit => self.find_best_fit(required_size),
+            };
 
-                prev = &mut (*current).next;
-                current = (*current).next;
+            let block = match found {
+                Some(block) => block,
+                None => return Err(allocator::AllocError::NoMemory),
+            };
+
+            Self::split_block(block, required_size);
+            let resume = (*block).next;
+            self.remove_block(block);
+            if self.policy == AllocPolicy::NextFit {
+                self.roving = if resume.is_null() { self.free_list } else { resume };
             }
-            Err(allocator::AllocError::NoMemory)
+
+            let ptr = (block as *mut u8).add(mem::size_of::<Block>());
+            self.used_size += required_size;
+            self.note_byte_frontier(block);
+            self.trace_event(TraceEvent::Alloc, ptr as usize, required_size);
+            Ok(NonNull::new(ptr).unwrap())
         }
     }
 
@@ -148,6 +543,7 @@ impl<const PAGE_SIZE: usize> ByteAllocator for EarlyAllocator<PAGE_SIZE> {
 
             self.merge_blocks();
         }
+        self.trace_event(TraceEvent::Dealloc, pos.as_ptr() as usize, layout.size().max(layout.align()));
     }
 
     fn available_bytes(&self) -> usize {
@@ -170,39 +566,87 @@ impl<const PAGE_SIZE: usize> PageAllocator for EarlyAllocator<PAGE_SIZE> {
         if align_pow2 % Self::PAGE_SIZE != 0 {
             return Err(allocator::AllocError::InvalidParam);
         }
-        let align_pow2 = align_pow2 / Self::PAGE_SIZE;
-        if !align_pow2.is_power_of_two() {
+        let align_pages = align_pow2 / Self::PAGE_SIZE;
+        if !align_pages.is_power_of_two() {
             return Err(allocator::AllocError::InvalidParam);
         }
 
-        let size = num_pages * Self::PAGE_SIZE;
-        if self.right_index - size <= self.left_index {
-            return Err(allocator::AllocError::NoMemory);
+        let pos = unsafe {
+            if num_pages == 1 && align_pages == 1 {
+                match self.find_free_page() {
+                    Some(index) => {
+                        self.set_page_used(index);
+                        let addr = self.page_addr(index);
+                        // Pages are carved from the top down; shrink the byte
+                        // ceiling so `alloc` can never grow into this page.
+                        self.right_index = self.right_index.min(addr);
+                        self.used_size += PAGE_SIZE;
+                        Ok(addr)
+                    }
+                    None => Err(allocator::AllocError::NoMemory),
+                }
+            } else {
+                match self.find_free_run(num_pages, align_pages) {
+                    Some(start_index) => {
+                        for index in start_index..start_index + num_pages {
+                            self.set_page_used(index);
+                        }
+                        let addr = self.page_addr(start_index);
+                        self.right_index = self.right_index.min(addr);
+                        self.used_size += num_pages * PAGE_SIZE;
+                        Ok(addr)
+                    }
+                    None => Err(allocator::AllocError::NoMemory),
+                }
+            }
+        };
+        if let Ok(addr) = pos {
+            self.trace_event(TraceEvent::Alloc, addr, num_pages * PAGE_SIZE);
         }
-        self.right_index -= size;
-        self.used_size += size;
-        Ok(self.right_index)
+        pos
     }
 
     fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
-        let size = num_pages * Self::PAGE_SIZE;
-        if pos == self.right_index {
-            self.right_index += size;
-            self.used_size -= size;
+        // Reject anything that doesn't describe a run we could actually have
+        // handed out, instead of indexing out of bounds or silently
+        // underflowing `used_size` on a bad `pos`/`num_pages`.
+        if num_pages == 0 || pos < self.page_base || (pos - self.page_base) % PAGE_SIZE != 0 {
+            return;
+        }
+        let start_index = (pos - self.page_base) / PAGE_SIZE;
+        if start_index + num_pages > self.total_page_count {
+            return;
         }
 
+        unsafe {
+            for index in start_index..start_index + num_pages {
+                self.clear_page_used(index);
+            }
+
+            // If the freed run sits right at the current byte ceiling, grow
+            // it back out, continuing through any already-free pages above
+            // it so the boundary doesn't get stuck below freed memory.
+            if pos == self.right_index {
+                let mut index = start_index + num_pages;
+                while index < self.total_page_count && !self.is_page_used(index) {
+                    index += 1;
+                }
+                self.right_index = self.page_addr(index);
+            }
+        }
+        self.used_size -= num_pages * PAGE_SIZE;
+        self.trace_event(TraceEvent::Dealloc, pos, num_pages * PAGE_SIZE);
     }
 
     fn available_pages(&self) -> usize {
-        self.total_size - self.used_size
+        self.total_page_count - self.used_pages()
     }
 
     fn total_pages(&self) -> usize {
-        self.total_size
+        self.total_page_count
     }
 
     fn used_pages(&self) -> usize {
-        self.used_size
+        unsafe { self.population() }
     }
 }
-
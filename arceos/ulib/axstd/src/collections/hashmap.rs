@@ -70,6 +70,96 @@ where
         }
     }
 
+    /// 键是否存在
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// 当前元素数量
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 表是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 查找键对应的值，不存在则插入 `default()` 的结果并返回其可变引用
+    pub fn get_or_insert_with<F>(&mut self, key: K, default: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        if self.len >= self.capacity * 3 / 4 {
+            self.resize();
+        }
+
+        let mut index = self.hash(&key);
+        loop {
+            index &= self.capacity - 1;
+            match &self.buckets[index] {
+                Some((k, _)) if *k == key => break,
+                None => {
+                    self.buckets[index] = Some((key, default()));
+                    self.len += 1;
+                    break;
+                }
+                _ => index += 1,
+            }
+        }
+
+        match &mut self.buckets[index] {
+            Some((_, v)) => v,
+            None => unreachable!(),
+        }
+    }
+
+    /// 删除键，返回被删除的值
+    ///
+    /// 采用后向位移删除（backward-shift deletion）：腾出槽位后，沿探测序列
+    /// 向前扫描后续已占用的槽位，若某个槽位上的元素"回退"到被腾出的位置仍
+    /// 落在它自己的探测序列内，就把它搬过去并继续从新腾出的位置扫描，直到
+    /// 遇到空槽为止。这样可以避免使用墓碑（tombstone），让 `get` 的
+    /// `None` 终止探测继续保持正确。
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let mut index = self.hash(key);
+        loop {
+            index &= self.capacity - 1;
+            match &self.buckets[index] {
+                Some((k, _)) if k == key => break,
+                None => return None,
+                _ => index += 1,
+            }
+        }
+
+        let (_, value) = self.buckets[index].take().unwrap();
+        self.len -= 1;
+
+        let mut gap = index;
+        let mut scan = (gap + 1) & (self.capacity - 1);
+        loop {
+            let ideal = match &self.buckets[scan] {
+                Some((k, _)) => self.hash(k) & (self.capacity - 1),
+                None => break,
+            };
+            // `scan` 处的元素能否搬回 `gap`：取决于它的理想位置 `ideal` 是否
+            // 落在 `(gap, scan]` 这段环形区间之外。
+            let movable = if scan >= gap {
+                ideal <= gap || ideal > scan
+            } else {
+                ideal <= gap && ideal > scan
+            };
+            if movable {
+                self.buckets[gap] = self.buckets[scan].take();
+                gap = scan;
+            }
+            scan = (scan + 1) & (self.capacity - 1);
+        }
+
+        self.shrink_if_sparse();
+        Some(value)
+    }
+
     /// 哈希函数
     fn hash(&self, key: &K) -> usize {
         let mut hasher = DefaultHasher::new();
@@ -79,7 +169,18 @@ where
 
     /// 扩容（重建哈希表）
     fn resize(&mut self) {
-        let new_capacity = self.capacity * 2;
+        self.resize_to(self.capacity * 2);
+    }
+
+    /// 删除比例较高时缩容，避免大量空桶拖慢探测
+    fn shrink_if_sparse(&mut self) {
+        if self.capacity > Self::INITIAL_CAPACITY && self.len * 4 < self.capacity {
+            self.resize_to((self.capacity / 2).max(Self::INITIAL_CAPACITY));
+        }
+    }
+
+    /// 按给定容量重建哈希表
+    fn resize_to(&mut self, new_capacity: usize) {
         let mut new_map = Self {
             buckets: Self::allocate_buckets(new_capacity),
             len: 0,